@@ -1,12 +1,10 @@
 //! Correlation functions.
 
 use csr;
+use sparse_walk::{walk, Step};
 
 /// Pearson correlation.
-fn pearson(a: csr::Row, b: csr::Row) -> f64 {
-    let mut peekable_a = a.iter().peekable();
-    let mut peekable_b = b.iter().peekable();
-
+pub(crate) fn pearson(a: csr::Row, b: csr::Row) -> f64 {
     let mut n = 0;
     let mut sum_a = 0.0;
     let mut sum_squared_a = 0.0;
@@ -14,22 +12,16 @@ fn pearson(a: csr::Row, b: csr::Row) -> f64 {
     let mut sum_squared_b = 0.0;
     let mut product_sum = 0.0;
 
-    while let (Some(&value_a), Some(&value_b)) = (peekable_a.peek(), peekable_b.peek()) {
-        if value_a.column < value_b.column {
-            peekable_a.next();
-        } else if value_a.column > value_b.column {
-            peekable_b.next();
-        } else {
+    walk(a, b, |step| {
+        if let Step::Both(value_a, value_b) = step {
             n += 1;
-            sum_a += value_a.value;
-            sum_squared_a += value_a.value * value_a.value;
-            sum_b += value_b.value;
-            sum_squared_b += value_b.value * value_b.value;
-            product_sum += value_a.value * value_b.value;
-            peekable_a.next();
-            peekable_b.next();
+            sum_a += value_a;
+            sum_squared_a += value_a * value_a;
+            sum_b += value_b;
+            sum_squared_b += value_b * value_b;
+            product_sum += value_a * value_b;
         }
-    }
+    });
 
     if n == 0 {
         return 0.0;
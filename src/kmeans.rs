@@ -1,9 +1,17 @@
 //! K-Means Clustering.
 
+use std::f64;
+
 use rand::{Rng, thread_rng};
 
-use corr;
 use csr::{Csr, Row};
+use metric::{Metric, Pearson};
+
+/// Number of utility-based split/merge attempts tried per ELBG step.
+const ELBG_SHIFT_ATTEMPTS: usize = 3;
+
+/// Half-width of the random offset used to split a centroid in two.
+const ELBG_SPLIT_PERTURBATION: f64 = 0.01;
 
 pub struct Model {
     row_count: usize,
@@ -11,11 +19,28 @@ pub struct Model {
     cluster_count: usize,
     centroids: Csr,
     row_clusters: Vec<Option<usize>>,
+    /// Per-cluster distortion, used by `make_elbg_step` to pick split/merge candidates.
+    cluster_distortion: Vec<f64>,
+    /// Similarity metric used to compare rows to centroids.
+    metric: Box<Metric>,
+    /// DP-means distance threshold; rows further than this from their nearest
+    /// centroid spawn a new cluster instead of being forced in.
+    dp_lambda: Option<f64>,
 }
 
 impl Model {
-    /// Creates a new model.
+    /// Creates a new model, comparing rows under Pearson correlation.
     pub fn new(row_count: usize, column_count: usize, cluster_count: usize) -> Self {
+        Self::with_metric(row_count, column_count, cluster_count, Box::new(Pearson))
+    }
+
+    /// Creates a new model whose rows are compared using the given metric.
+    pub fn with_metric(
+        row_count: usize,
+        column_count: usize,
+        cluster_count: usize,
+        metric: Box<Metric>,
+    ) -> Self {
         let mut centroids = Csr::new();
         let mut rng = thread_rng();
 
@@ -33,9 +58,108 @@ impl Model {
             cluster_count: cluster_count,
             centroids: centroids,
             row_clusters: vec![None; row_count],
+            cluster_distortion: vec![0.0; cluster_count],
+            metric: metric,
+            dp_lambda: None,
+        }
+    }
+
+    /// Creates a new model using k-means++ seeding under Pearson correlation.
+    /// See `with_kmeans_pp_and_metric` for the general form.
+    pub fn with_kmeans_pp(matrix: &Csr, cluster_count: usize) -> Self {
+        Self::with_kmeans_pp_and_metric(matrix, cluster_count, Box::new(Pearson))
+    }
+
+    /// Creates a new model using k-means++ seeding: the first centroid is a
+    /// randomly chosen data row, and each subsequent centroid is sampled from
+    /// the remaining rows with probability proportional to its squared
+    /// distance (under the given metric) to the nearest centroid chosen so far.
+    pub fn with_kmeans_pp_and_metric(matrix: &Csr, cluster_count: usize, metric: Box<Metric>) -> Self {
+        let row_count = matrix.row_count();
+        let column_count = matrix.column_count();
+        let mut rng = thread_rng();
+
+        // Rows with fewer than 3 values are never valid seeds (same as `make_step`).
+        let eligible_rows: Vec<usize> =
+            (0..row_count).filter(|&row_index| matrix.get_row(row_index).len() >= 3).collect();
+
+        let mut chosen_rows: Vec<usize> = Vec::with_capacity(cluster_count);
+        if !eligible_rows.is_empty() {
+            chosen_rows.push(eligible_rows[rng.gen_range(0, eligible_rows.len())]);
+
+            while chosen_rows.len() < cluster_count {
+                let mut squared_distances = Vec::with_capacity(eligible_rows.len());
+                let mut total_squared_distance = 0.0;
+                for &row_index in &eligible_rows {
+                    let row = matrix.get_row(row_index);
+                    let mut min_distance = f64::INFINITY;
+                    for &chosen_row_index in &chosen_rows {
+                        let distance = metric.distance(row, matrix.get_row(chosen_row_index));
+                        if distance < min_distance {
+                            min_distance = distance;
+                        }
+                    }
+                    let squared_distance = min_distance * min_distance;
+                    total_squared_distance += squared_distance;
+                    squared_distances.push(squared_distance);
+                }
+
+                // All distances zero (every eligible row already chosen, or a
+                // duplicate): fall back to picking uniformly at random.
+                let next_row_index = if total_squared_distance > 0.0 {
+                    let mut target = rng.gen_range(0.0, total_squared_distance);
+                    let mut picked = *eligible_rows.last().unwrap();
+                    for (i, &squared_distance) in squared_distances.iter().enumerate() {
+                        if target < squared_distance {
+                            picked = eligible_rows[i];
+                            break;
+                        }
+                        target -= squared_distance;
+                    }
+                    picked
+                } else {
+                    eligible_rows[rng.gen_range(0, eligible_rows.len())]
+                };
+                chosen_rows.push(next_row_index);
+            }
+        }
+
+        // Falls back to zeroed centroids if there was no eligible row to seed from.
+        let mut centroids = Csr::new();
+        for cluster_index in 0..cluster_count {
+            centroids.start();
+            let mut dense = vec![0.0; column_count];
+            if let Some(&row_index) = chosen_rows.get(cluster_index) {
+                for value in matrix.get_row(row_index).iter() {
+                    dense[value.column] = value.value;
+                }
+            }
+            for (column_index, &value) in dense.iter().enumerate() {
+                centroids.next(column_index, value);
+            }
+        }
+        centroids.start();
+
+        Model {
+            row_count: row_count,
+            column_count: column_count,
+            cluster_count: cluster_count,
+            centroids: centroids,
+            row_clusters: vec![None; row_count],
+            cluster_distortion: vec![0.0; cluster_count],
+            metric: metric,
+            dp_lambda: None,
         }
     }
 
+    /// Creates a new DP-means model: instead of a fixed cluster count, rows
+    /// further than `lambda` from their nearest centroid spawn a new cluster.
+    pub fn new_dp(row_count: usize, column_count: usize, lambda: f64) -> Self {
+        let mut model = Self::with_metric(row_count, column_count, 0, Box::new(Pearson));
+        model.dp_lambda = Some(lambda);
+        model
+    }
+
     /// Gets cluster count.
     pub fn cluster_count(&self) -> usize {
         self.cluster_count
@@ -55,6 +179,7 @@ impl Model {
     pub fn make_step(&mut self, matrix: &Csr) -> f64 {
         let mut total_count = 0;
         let mut error_sum = 0.0;
+        let mut cluster_distortion = vec![0.0; self.cluster_count];
         // Assign nearest centroids.
         for row_index in 0..self.row_count {
             let row = matrix.get_row(row_index);
@@ -63,11 +188,254 @@ impl Model {
                 continue;
             }
             let (cluster_index, distance) = self.get_nearest_centroid(row);
+            if let Some(lambda) = self.dp_lambda {
+                if distance > lambda {
+                    // No existing centroid is close enough: spawn a new
+                    // cluster centered on this row instead of forcing it in.
+                    let new_cluster_index = self.spawn_centroid_from_row(row);
+                    self.row_clusters[row_index] = Some(new_cluster_index);
+                    total_count += 1;
+                    cluster_distortion.push(0.0);
+                    continue;
+                }
+            }
             self.row_clusters[row_index] = Some(cluster_index);
             total_count += 1;
             error_sum += distance * distance;
+            cluster_distortion[cluster_index] += distance * distance;
+        }
+        self.cluster_distortion = cluster_distortion;
+        self.recompute_centroids(matrix);
+        // DP-means drops clusters a spawn left empty instead of reseeding them.
+        self.drop_empty_clusters();
+
+        error_sum / total_count as f64
+    }
+
+    /// Appends a new centroid seeded from `row` and returns its index.
+    fn spawn_centroid_from_row(&mut self, row: Row) -> usize {
+        let mut dense = vec![0.0; self.column_count];
+        for value in row.iter() {
+            dense[value.column] = value.value;
+        }
+
+        self.centroids.start();
+        for (column_index, &value) in dense.iter().enumerate() {
+            self.centroids.next(column_index, value);
+        }
+        self.centroids.start();
+
+        let new_cluster_index = self.cluster_count;
+        self.cluster_count += 1;
+        new_cluster_index
+    }
+
+    /// Drops clusters left with no assigned rows after a DP-means step,
+    /// compacting the remaining cluster indices (and their distortions) down.
+    fn drop_empty_clusters(&mut self) {
+        if self.dp_lambda.is_none() {
+            return;
+        }
+
+        let mut has_members = vec![false; self.cluster_count];
+        for &cluster_index in self.row_clusters.iter().filter_map(|c| c.as_ref()) {
+            has_members[cluster_index] = true;
+        }
+        if has_members.iter().all(|&present| present) {
+            return;
+        }
+
+        let mut remap = vec![None; self.cluster_count];
+        let mut centroids = Csr::new();
+        let mut cluster_distortion = Vec::new();
+        let mut next_index = 0;
+        for cluster_index in 0..self.cluster_count {
+            if !has_members[cluster_index] {
+                continue;
+            }
+            remap[cluster_index] = Some(next_index);
+            next_index += 1;
+
+            centroids.start();
+            for value in self.centroids.get_row(cluster_index).iter() {
+                centroids.next(value.column, value.value);
+            }
+            cluster_distortion.push(self.cluster_distortion[cluster_index]);
         }
-        // Reset centroids.
+        centroids.start();
+
+        for row_index in 0..self.row_count {
+            self.row_clusters[row_index] =
+                self.row_clusters[row_index].and_then(|cluster_index| remap[cluster_index]);
+        }
+        self.centroids = centroids;
+        self.cluster_count = next_index;
+        self.cluster_distortion = cluster_distortion;
+    }
+
+    /// Makes a clustering step using the ELBG (Enhanced LBG) heuristic: a
+    /// normal `make_step`, followed by a bounded number of utility-based
+    /// split/merge shifts to escape local optima.
+    pub fn make_elbg_step(&mut self, matrix: &Csr) -> f64 {
+        let error = self.make_step(matrix);
+
+        let mut rng = thread_rng();
+        for _ in 0..ELBG_SHIFT_ATTEMPTS {
+            self.try_elbg_shift(matrix, &mut rng);
+        }
+
+        error
+    }
+
+    /// Dissolves the lowest-utility cluster and splits the highest-utility
+    /// one, keeping the change only if it reduces their combined distortion.
+    fn try_elbg_shift<R: Rng>(&mut self, matrix: &Csr, rng: &mut R) {
+        if self.cluster_count < 2 {
+            return;
+        }
+
+        let mean_distortion =
+            self.cluster_distortion.iter().sum::<f64>() / self.cluster_count as f64;
+        if mean_distortion <= 0.0 {
+            return;
+        }
+
+        let mut low_index = 0;
+        let mut low_utility = f64::INFINITY;
+        let mut high_index = 0;
+        let mut high_utility = f64::NEG_INFINITY;
+        for cluster_index in 0..self.cluster_count {
+            let utility = self.cluster_distortion[cluster_index] / mean_distortion;
+            if utility < low_utility {
+                low_utility = utility;
+                low_index = cluster_index;
+            }
+            if utility > high_utility {
+                high_utility = utility;
+                high_index = cluster_index;
+            }
+        }
+        if low_index == high_index || low_utility >= 1.0 {
+            return;
+        }
+
+        // Snapshot what we're about to touch so the shift can be reverted.
+        let low_values: Vec<f64> = self.centroids.get_row(low_index).iter().map(|v| v.value).collect();
+        let high_values: Vec<f64> = self.centroids.get_row(high_index).iter().map(|v| v.value).collect();
+        let distortion_before = self.cluster_distortion[low_index] + self.cluster_distortion[high_index];
+        let affected_rows: Vec<usize> = (0..self.row_count)
+            .filter(|&row_index| {
+                self.row_clusters[row_index] == Some(low_index)
+                    || self.row_clusters[row_index] == Some(high_index)
+            })
+            .collect();
+        let saved_clusters: Vec<Option<usize>> =
+            affected_rows.iter().map(|&row_index| self.row_clusters[row_index]).collect();
+
+        // Dissolve the low-utility centroid and reuse its slot as the other
+        // half of the high-utility cluster's split.
+        {
+            let centroid = self.centroids.get_mutable_row(low_index);
+            for (value, &source) in centroid.iter_mut().zip(high_values.iter()) {
+                value.value = source + rng.gen_range(-ELBG_SPLIT_PERTURBATION, ELBG_SPLIT_PERTURBATION);
+            }
+        }
+        {
+            let centroid = self.centroids.get_mutable_row(high_index);
+            for (value, &source) in centroid.iter_mut().zip(high_values.iter()) {
+                value.value = source + rng.gen_range(-ELBG_SPLIT_PERTURBATION, ELBG_SPLIT_PERTURBATION);
+            }
+        }
+
+        // Reassign the affected rows locally, between just these two centroids.
+        for &row_index in &affected_rows {
+            let row = matrix.get_row(row_index);
+            let distance_low = self.metric.distance(row, self.centroids.get_row(low_index));
+            let distance_high = self.metric.distance(row, self.centroids.get_row(high_index));
+            self.row_clusters[row_index] =
+                Some(if distance_low < distance_high { low_index } else { high_index });
+        }
+
+        // Recompute the touched centroids and their distortion from their new members.
+        let (low_distortion, high_distortion) =
+            self.recompute_touched_centroids(matrix, low_index, high_index);
+        let distortion_after = low_distortion + high_distortion;
+
+        if distortion_after < distortion_before {
+            self.cluster_distortion[low_index] = low_distortion;
+            self.cluster_distortion[high_index] = high_distortion;
+        } else {
+            // Revert: restore centroid values and row assignments.
+            {
+                let centroid = self.centroids.get_mutable_row(low_index);
+                for (value, &saved) in centroid.iter_mut().zip(low_values.iter()) {
+                    value.value = saved;
+                }
+            }
+            {
+                let centroid = self.centroids.get_mutable_row(high_index);
+                for (value, &saved) in centroid.iter_mut().zip(high_values.iter()) {
+                    value.value = saved;
+                }
+            }
+            for (&row_index, &cluster) in affected_rows.iter().zip(saved_clusters.iter()) {
+                self.row_clusters[row_index] = cluster;
+            }
+        }
+    }
+
+    /// Recomputes the two given centroids and returns their new distortion.
+    /// A cluster left with no members divides to NaN and reports a NaN
+    /// distortion, which just loses the comparison in the caller.
+    fn recompute_touched_centroids(&mut self, matrix: &Csr, a: usize, b: usize) -> (f64, f64) {
+        let mut sums = [vec![0.0; self.column_count], vec![0.0; self.column_count]];
+        let mut counts = [vec![0usize; self.column_count], vec![0usize; self.column_count]];
+
+        for row_index in 0..self.row_count {
+            let slot = match self.row_clusters[row_index] {
+                Some(cluster_index) if cluster_index == a => 0,
+                Some(cluster_index) if cluster_index == b => 1,
+                _ => continue,
+            };
+            for value in matrix.get_row(row_index) {
+                sums[slot][value.column] += value.value;
+                counts[slot][value.column] += 1;
+            }
+        }
+
+        for (slot, &cluster_index) in [a, b].iter().enumerate() {
+            let centroid = self.centroids.get_mutable_row(cluster_index);
+            for value in centroid.iter_mut() {
+                value.value = sums[slot][value.column] / counts[slot][value.column] as f64;
+            }
+        }
+
+        let mut distortion = [0.0, 0.0];
+        let mut members = [0usize, 0usize];
+        for row_index in 0..self.row_count {
+            let slot = match self.row_clusters[row_index] {
+                Some(cluster_index) if cluster_index == a => 0,
+                Some(cluster_index) if cluster_index == b => 1,
+                _ => continue,
+            };
+            let row = matrix.get_row(row_index);
+            let cluster_index = [a, b][slot];
+            let distance = self.metric.distance(row, self.centroids.get_row(cluster_index));
+            distortion[slot] += distance * distance;
+            members[slot] += 1;
+        }
+        for slot in 0..2 {
+            if members[slot] == 0 {
+                distortion[slot] = f64::NAN;
+            }
+        }
+
+        (distortion[0], distortion[1])
+    }
+
+    /// Recomputes every centroid from its currently assigned rows, reseeding
+    /// any cluster that got no members instead of dividing to NaN.
+    fn recompute_centroids(&mut self, matrix: &Csr) {
         for cluster_index in 0..self.cluster_count {
             let row = self.centroids.get_mutable_row(cluster_index);
             for value in row.iter_mut() {
@@ -76,8 +444,10 @@ impl Model {
         }
         // Sum up values.
         let mut value_count = vec![0usize; self.cluster_count * self.column_count];
+        let mut row_count = vec![0usize; self.cluster_count];
         for row_index in 0..self.row_count {
             if let Some(cluster_index) = self.row_clusters[row_index] {
+                row_count[cluster_index] += 1;
                 for value in matrix.get_row(row_index) {
                     // Increase column value count.
                     value_count[cluster_index * self.column_count + value.column] += 1;
@@ -86,25 +456,57 @@ impl Model {
                 }
             }
         }
-        // Divide by value count.
+        // Reseed empty clusters, unless DP-means is about to drop them anyway.
+        if row_count.iter().any(|&count| count == 0) && self.dp_lambda.is_none() {
+            let eligible_rows: Vec<usize> =
+                (0..self.row_count).filter(|&row_index| matrix.get_row(row_index).len() >= 3).collect();
+            let mut rng = thread_rng();
+            for cluster_index in 0..self.cluster_count {
+                if row_count[cluster_index] == 0 {
+                    self.reseed_centroid(cluster_index, matrix, &eligible_rows, &mut rng);
+                }
+            }
+        }
         for cluster_index in 0..self.cluster_count {
-            for value in self.centroids.get_mutable_row(cluster_index) {
-                value.value /= value_count[cluster_index * self.column_count + value.column] as f64;
+            if row_count[cluster_index] != 0 {
+                for value in self.centroids.get_mutable_row(cluster_index) {
+                    value.value /= value_count[cluster_index * self.column_count + value.column] as f64;
+                }
             }
         }
+    }
 
-        error_sum / total_count as f64
+    /// Reseeds an empty cluster's centroid from a random row in `eligible_rows`,
+    /// or leaves it zeroed if `eligible_rows` is empty.
+    fn reseed_centroid<R: Rng>(
+        &mut self,
+        cluster_index: usize,
+        matrix: &Csr,
+        eligible_rows: &[usize],
+        rng: &mut R,
+    ) {
+        let centroid = self.centroids.get_mutable_row(cluster_index);
+        for value in centroid.iter_mut() {
+            value.value = 0.0;
+        }
+
+        if eligible_rows.is_empty() {
+            return;
+        }
+
+        let row_index = eligible_rows[rng.gen_range(0, eligible_rows.len())];
+        for value in matrix.get_row(row_index).iter() {
+            self.centroids.get_mutable_row(cluster_index)[value.column].value = value.value;
+        }
     }
 
     /// Gets the nearest centroid by the given row.
     fn get_nearest_centroid(&self, row: Row) -> (usize, f64) {
-        use std::f64;
-
         let mut min_distance = f64::INFINITY;
         let mut cluster_index = 0;
 
         for i in 0..self.cluster_count {
-            let distance = 1.0 - corr::pearson(row, self.centroids.get_row(i));
+            let distance = self.metric.distance(row, self.centroids.get_row(i));
             if distance < min_distance {
                 min_distance = distance;
                 cluster_index = i;
@@ -114,3 +516,110 @@ impl Model {
         (cluster_index, min_distance)
     }
 }
+
+#[cfg(test)]
+fn fixture() -> Csr {
+    let mut table = Csr::new();
+
+    table.start();
+    table.next(0, 1.0);
+    table.next(1, 2.0);
+    table.next(2, 3.0);
+
+    table.start();
+    table.next(0, 2.0);
+    table.next(1, 4.0);
+    table.next(2, 6.0);
+
+    table.start();
+    table.next(0, 5.0);
+    table.next(1, 1.0);
+    table.next(2, 3.0);
+
+    table.start();
+    table.next(0, 6.0);
+    table.next(1, 2.0);
+    table.next(2, 4.0);
+
+    table.start();
+
+    table
+}
+
+#[test]
+fn test_with_kmeans_pp_seeds_from_data_rows() {
+    let matrix = fixture();
+    let model = Model::with_kmeans_pp(&matrix, 2);
+
+    assert_eq!(model.cluster_count(), 2);
+
+    // Every seeded centroid must be an exact (dense) copy of one of the
+    // matrix's rows, not an arbitrary random point like `new` produces.
+    for cluster_index in 0..model.cluster_count() {
+        let centroid: Vec<f64> = model.get_centroid(cluster_index).iter().map(|v| v.value).collect();
+        let matches_a_row = (0..4).any(|row_index| {
+            let mut dense = vec![0.0; 3];
+            for value in matrix.get_row(row_index).iter() {
+                dense[value.column] = value.value;
+            }
+            dense == centroid
+        });
+        assert!(matches_a_row);
+    }
+}
+
+#[test]
+fn test_new_dp_grows_and_merges_clusters() {
+    let matrix = fixture();
+    // Rows 0-1 and rows 2-3 are each perfectly correlated with each other
+    // (Pearson distance 0.0) and anti-correlated across the two pairs
+    // (Pearson distance 1.5), so a threshold between the two cleanly
+    // separates the data into exactly those two clusters.
+    let mut model = Model::new_dp(4, 3, 1.0);
+
+    model.make_step(&matrix);
+
+    assert_eq!(model.cluster_count(), 2);
+    assert_eq!(model.get_cluster(0), model.get_cluster(1));
+    assert_eq!(model.get_cluster(2), model.get_cluster(3));
+    assert!(model.get_cluster(0) != model.get_cluster(2));
+
+    let mut centroids: Vec<Vec<f64>> = (0..model.cluster_count())
+        .map(|cluster_index| model.get_centroid(cluster_index).iter().map(|v| v.value).collect())
+        .collect();
+    centroids.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+    assert_eq!(centroids, vec![vec![1.5, 3.0, 4.5], vec![5.5, 1.5, 3.5]]);
+}
+
+#[test]
+fn test_make_elbg_step_keeps_centroids_finite() {
+    let matrix = fixture();
+    let mut model = Model::new(4, 3, 2);
+    model.make_step(&matrix);
+
+    let error = model.make_elbg_step(&matrix);
+
+    assert!(error.is_finite());
+    assert_eq!(model.cluster_count(), 2);
+    for cluster_index in 0..model.cluster_count() {
+        for value in model.get_centroid(cluster_index) {
+            assert!(value.value.is_finite());
+        }
+    }
+}
+
+#[test]
+fn test_make_step_reseeds_empty_clusters_instead_of_nan() {
+    let matrix = fixture();
+    // More clusters than rows guarantees at least one cluster gets no rows
+    // assigned on the first pass.
+    let mut model = Model::new(4, 3, 6);
+
+    model.make_step(&matrix);
+
+    for cluster_index in 0..model.cluster_count() {
+        for value in model.get_centroid(cluster_index) {
+            assert!(!value.value.is_nan());
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Pluggable similarity metrics for sparse CSR rows.
+
+use corr;
+use csr::{Csr, Row};
+use sparse_walk::{walk, Step};
+
+/// A distance between two sparse rows. Smaller means more similar; `Model`
+/// always picks the centroid that minimizes this.
+pub trait Metric {
+    fn distance(&self, a: Row, b: Row) -> f64;
+}
+
+/// Pearson correlation distance: `1 - pearson(a, b)`.
+pub struct Pearson;
+
+impl Metric for Pearson {
+    fn distance(&self, a: Row, b: Row) -> f64 {
+        1.0 - corr::pearson(a, b)
+    }
+}
+
+/// Cosine distance: `1 - cosine_similarity(a, b)`.
+pub struct Cosine;
+
+impl Metric for Cosine {
+    fn distance(&self, a: Row, b: Row) -> f64 {
+        let mut norm_a = 0.0;
+        let mut norm_b = 0.0;
+        let mut dot_product = 0.0;
+
+        walk(a, b, |step| match step {
+            Step::Both(value_a, value_b) => {
+                norm_a += value_a * value_a;
+                norm_b += value_b * value_b;
+                dot_product += value_a * value_b;
+            }
+            Step::OnlyA(value_a) => norm_a += value_a * value_a,
+            Step::OnlyB(value_b) => norm_b += value_b * value_b,
+        });
+
+        let denominator = norm_a.sqrt() * norm_b.sqrt();
+        if denominator > 0.000001 { 1.0 - dot_product / denominator } else { 1.0 }
+    }
+}
+
+/// Euclidean distance over the union of both rows' columns (missing columns
+/// are treated as zero).
+pub struct Euclidean;
+
+impl Metric for Euclidean {
+    fn distance(&self, a: Row, b: Row) -> f64 {
+        let mut sum_squared = 0.0;
+
+        walk(a, b, |step| match step {
+            Step::Both(value_a, value_b) => {
+                let difference = value_a - value_b;
+                sum_squared += difference * difference;
+            }
+            Step::OnlyA(value_a) => sum_squared += value_a * value_a,
+            Step::OnlyB(value_b) => sum_squared += value_b * value_b,
+        });
+
+        sum_squared.sqrt()
+    }
+}
+
+/// Tanimoto (Jaccard) distance for binary fingerprint rows: `1 - |a ∩ b| / |a
+/// ∪ b|`, where a present column counts as a set bit regardless of its value.
+pub struct Tanimoto;
+
+impl Metric for Tanimoto {
+    fn distance(&self, a: Row, b: Row) -> f64 {
+        let mut intersection = 0usize;
+        let mut union = 0usize;
+
+        walk(a, b, |step| match step {
+            Step::Both(_, _) => {
+                intersection += 1;
+                union += 1;
+            }
+            Step::OnlyA(_) | Step::OnlyB(_) => union += 1,
+        });
+
+        if union == 0 { 1.0 } else { 1.0 - intersection as f64 / union as f64 }
+    }
+}
+
+#[cfg(test)]
+fn fixture() -> Csr {
+    let mut table = Csr::new();
+
+    table.start();
+    table.next(0, 1.0);
+    table.next(1, 3.0);
+    table.next(2, 5.0);
+    table.next(3, 2.0);
+
+    // Shares columns 0, 1 and 2 with row 0; column 3 only exists in row 0,
+    // column 4 only in row 1.
+    table.start();
+    table.next(0, 2.0);
+    table.next(1, 2.0);
+    table.next(2, 6.0);
+    table.next(4, 1.0);
+
+    table.start();
+
+    table
+}
+
+#[test]
+fn test_pearson_metric() {
+    let table = fixture();
+    assert_eq!(Pearson.distance(table.get_row(0), table.get_row(1)), 0.1339745962155613);
+}
+
+#[test]
+fn test_cosine() {
+    let table = fixture();
+    assert_eq!(Cosine.distance(table.get_row(0), table.get_row(1)), 0.09292115955005165);
+}
+
+#[test]
+fn test_euclidean() {
+    let table = fixture();
+    assert_eq!(Euclidean.distance(table.get_row(0), table.get_row(1)), 2.8284271247461903);
+}
+
+#[test]
+fn test_tanimoto() {
+    let table = fixture();
+    assert_eq!(Tanimoto.distance(table.get_row(0), table.get_row(1)), 0.4);
+}
@@ -0,0 +1,169 @@
+//! Self-Organizing Map.
+
+use std::f64;
+
+use rand::{Rng, thread_rng};
+
+use csr::{Csr, Row};
+use metric::{Metric, Pearson};
+
+/// Learning rate at epoch 0, decaying toward 0 as training progresses.
+const INITIAL_LEARNING_RATE: f64 = 0.5;
+
+/// How quickly the learning rate decays over the course of training.
+const LEARNING_RATE_DECAY: f64 = 3.0;
+
+/// How quickly the neighborhood radius decays over the course of training.
+const RADIUS_DECAY: f64 = 3.0;
+
+pub struct SelfOrganizingMap {
+    width: usize,
+    height: usize,
+    column_count: usize,
+    nodes: Csr,
+    metric: Box<Metric>,
+}
+
+impl SelfOrganizingMap {
+    /// Creates a new `width x height` map with uniform-random node
+    /// centroids, compared under Pearson correlation.
+    pub fn new(width: usize, height: usize, column_count: usize) -> Self {
+        Self::with_metric(width, height, column_count, Box::new(Pearson))
+    }
+
+    /// Creates a new map whose nodes are compared using the given metric.
+    pub fn with_metric(width: usize, height: usize, column_count: usize, metric: Box<Metric>) -> Self {
+        let mut nodes = Csr::new();
+        let mut rng = thread_rng();
+
+        for _ in 0..(width * height) {
+            nodes.start();
+            for column_index in 0..column_count {
+                nodes.next(column_index, rng.gen_range(0.0, 100.0));
+            }
+        }
+        nodes.start();
+
+        SelfOrganizingMap {
+            width: width,
+            height: height,
+            column_count: column_count,
+            nodes: nodes,
+            metric: metric,
+        }
+    }
+
+    /// Gets the grid width.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Gets the grid height.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Gets the number of columns each node's centroid has.
+    pub fn column_count(&self) -> usize {
+        self.column_count
+    }
+
+    /// Gets a node's centroid, addressed by its grid coordinate.
+    pub fn get_centroid(&self, x: usize, y: usize) -> Row {
+        self.nodes.get_row(self.node_index(x, y))
+    }
+
+    /// Finds the best-matching unit for `row`: the grid coordinate of the
+    /// node closest to it under the map's metric.
+    pub fn get_bmu(&self, row: Row) -> (usize, usize) {
+        let mut min_distance = f64::INFINITY;
+        let mut bmu_index = 0;
+
+        for node_index in 0..self.width * self.height {
+            let distance = self.metric.distance(row, self.nodes.get_row(node_index));
+            if distance < min_distance {
+                min_distance = distance;
+                bmu_index = node_index;
+            }
+        }
+
+        (bmu_index % self.width, bmu_index / self.width)
+    }
+
+    /// Trains the map on a single input row: finds its best-matching unit,
+    /// then pulls it and its grid neighbors toward the row.
+    pub fn train_step(&mut self, row: Row, epoch: usize, epoch_count: usize) {
+        let (bmu_x, bmu_y) = self.get_bmu(row);
+
+        let progress = epoch as f64 / epoch_count as f64;
+        let learning_rate = INITIAL_LEARNING_RATE * (-progress * LEARNING_RATE_DECAY).exp();
+        let initial_radius = (if self.width > self.height { self.width } else { self.height } as f64) / 2.0;
+        let radius = initial_radius * (-progress * RADIUS_DECAY).exp();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let dx = x as f64 - bmu_x as f64;
+                let dy = y as f64 - bmu_y as f64;
+                let grid_distance_squared = dx * dx + dy * dy;
+                if grid_distance_squared > radius * radius {
+                    continue;
+                }
+
+                let influence = (-grid_distance_squared / (2.0 * radius * radius)).exp();
+                let node = self.nodes.get_mutable_row(self.node_index(x, y));
+                for value in row.iter() {
+                    let delta = learning_rate * influence * (value.value - node[value.column].value);
+                    node[value.column].value += delta;
+                }
+            }
+        }
+    }
+
+    fn node_index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+}
+
+#[cfg(test)]
+fn fixture_row() -> Csr {
+    let mut table = Csr::new();
+    table.start();
+    table.next(0, 1.0);
+    table.next(1, 2.0);
+    table.next(2, 3.0);
+    table.start();
+    table
+}
+
+#[test]
+fn test_get_bmu_is_in_bounds_and_deterministic() {
+    use metric::Euclidean;
+
+    let matrix = fixture_row();
+    let row = matrix.get_row(0);
+    let map = SelfOrganizingMap::with_metric(3, 2, 3, Box::new(Euclidean));
+
+    let (x, y) = map.get_bmu(row);
+    assert!(x < map.width());
+    assert!(y < map.height());
+    // The map didn't change between the two calls, so the BMU must not either.
+    assert_eq!(map.get_bmu(row), (x, y));
+}
+
+#[test]
+fn test_train_step_moves_bmu_closer_to_the_input() {
+    use metric::Euclidean;
+
+    let matrix = fixture_row();
+    let row = matrix.get_row(0);
+    // A single node has no neighbors to dilute the pull, so it must move
+    // strictly closer to `row` every time it trains on it.
+    let mut map = SelfOrganizingMap::with_metric(1, 1, 3, Box::new(Euclidean));
+    let metric = Euclidean;
+
+    let distance_before = metric.distance(row, map.get_centroid(0, 0));
+    map.train_step(row, 0, 10);
+    let distance_after = metric.distance(row, map.get_centroid(0, 0));
+
+    assert!(distance_after < distance_before);
+}
@@ -0,0 +1,47 @@
+//! Shared merge-walk over two column-sorted CSR rows.
+
+use csr::Row;
+
+/// One step of walking `a` and `b` in increasing column order.
+pub enum Step {
+    /// Both rows have a value in this column.
+    Both(f64, f64),
+    /// Only `a` has a value in this column.
+    OnlyA(f64),
+    /// Only `b` has a value in this column.
+    OnlyB(f64),
+}
+
+/// Walks `a` and `b` in increasing column order, calling `visit` with a
+/// `Step` for every column present in either row.
+pub fn walk<F: FnMut(Step)>(a: Row, b: Row, mut visit: F) {
+    let mut peekable_a = a.iter().peekable();
+    let mut peekable_b = b.iter().peekable();
+
+    loop {
+        match (peekable_a.peek(), peekable_b.peek()) {
+            (Some(&value_a), Some(&value_b)) => {
+                if value_a.column < value_b.column {
+                    visit(Step::OnlyA(value_a.value));
+                    peekable_a.next();
+                } else if value_a.column > value_b.column {
+                    visit(Step::OnlyB(value_b.value));
+                    peekable_b.next();
+                } else {
+                    visit(Step::Both(value_a.value, value_b.value));
+                    peekable_a.next();
+                    peekable_b.next();
+                }
+            }
+            (Some(&value_a), None) => {
+                visit(Step::OnlyA(value_a.value));
+                peekable_a.next();
+            }
+            (None, Some(&value_b)) => {
+                visit(Step::OnlyB(value_b.value));
+                peekable_b.next();
+            }
+            (None, None) => break,
+        }
+    }
+}